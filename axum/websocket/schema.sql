@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS status_checks (
+  id BIGSERIAL PRIMARY KEY,
+  name TEXT NOT NULL,
+  checked_at TIMESTAMPTZ NOT NULL,
+  is_up BOOLEAN NOT NULL,
+  status_code INT,
+  latency_ms BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS status_checks_checked_at ON status_checks (checked_at);
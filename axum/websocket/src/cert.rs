@@ -0,0 +1,125 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use anyhow::anyhow;
+use chrono::{DateTime, TimeZone, Utc};
+use futures::{future::MapOk, TryFutureExt};
+use hyper::{
+    client::{
+        connect::{Connected, Connection},
+        HttpConnector,
+    },
+    service::Service,
+    Uri,
+};
+use hyper_tls::{native_tls, HttpsConnecting, HttpsConnector, MaybeHttpsStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tracing::warn;
+
+/// When the certificate of the connection a response came over expires, found in the extensions
+/// of responses from HTTPS targets.
+#[derive(Clone, Copy)]
+pub(crate) struct CertExpiry(pub(crate) DateTime<Utc>);
+
+/// Connects like `HttpsConnector`, but reads the certificate of each HTTPS connection it opens
+/// so the checks learn when it expires without connecting again.
+#[derive(Clone)]
+pub(crate) struct CertConnector(HttpsConnector<HttpConnector>);
+
+impl CertConnector {
+    pub(crate) fn new() -> Self {
+        Self(HttpsConnector::new())
+    }
+}
+
+impl Service<Uri> for CertConnector {
+    type Response = CertStream;
+    type Error = <HttpsConnector<HttpConnector> as Service<Uri>>::Error;
+    type Future = MapOk<HttpsConnecting<TcpStream>, fn(MaybeHttpsStream<TcpStream>) -> CertStream>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        self.0.call(uri).map_ok(CertStream)
+    }
+}
+
+/// A connection opened by `CertConnector`, passing its certificate's expiry on to hyper.
+pub(crate) struct CertStream(MaybeHttpsStream<TcpStream>);
+
+impl Connection for CertStream {
+    fn connected(&self) -> Connected {
+        let connected = self.0.connected();
+        let MaybeHttpsStream::Https(stream) = &self.0 else {
+            return connected;
+        };
+
+        match cert_expiry(stream.get_ref().peer_certificate()) {
+            Ok(expiry) => connected.extra(CertExpiry(expiry)),
+            Err(err) => {
+                warn!(%err, "failed to read the certificate");
+                connected
+            }
+        }
+    }
+}
+
+fn cert_expiry(
+    cert: Result<Option<native_tls::Certificate>, native_tls::Error>,
+) -> Result<DateTime<Utc>, anyhow::Error> {
+    let cert = cert?.ok_or_else(|| anyhow!("no certificate presented"))?;
+    let der = cert.to_der()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(&der)?;
+
+    Utc.timestamp_opt(cert.validity().not_after.timestamp(), 0)
+        .single()
+        .ok_or_else(|| anyhow!("the certificate expires out of range"))
+}
+
+impl AsyncRead for CertStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CertStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.0.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
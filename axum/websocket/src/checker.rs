@@ -0,0 +1,940 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use hyper::{
+    body::HttpBody,
+    header::{self, HeaderValue},
+    Body, Client, Method, Request, StatusCode, Uri,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, watch, Mutex, Notify, Semaphore},
+    task::JoinSet,
+    time::{sleep, timeout},
+};
+use tracing::{error, warn};
+
+use crate::{
+    cert::{CertConnector, CertExpiry},
+    metrics::Metrics,
+    State, HISTORY_CAPACITY,
+};
+
+/// How long a single check may take before the target is considered down
+pub(crate) const TIMEOUT_SECS: u64 = 10;
+/// How often to push the status to clients even when it did not change
+const HEARTBEAT_SECS: u64 = 60;
+/// How many of the latest checks the uptime and latency stats are computed over, one hour at the
+/// default interval
+const UPTIME_WINDOW: usize = 240;
+/// How many checks in a row have to fail before a target is reported as down
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long a target that was down has to pass every check before it is reported as up again. This
+/// doubles each time it fails again while recovering, so crash looping targets don't flash up.
+const RECOVERY_SECS: u64 = 60;
+const MAX_RECOVERY_SECS: u64 = 16 * RECOVERY_SECS;
+/// The most characters of a check's error to pass on, to keep the frames small
+const MAX_ERROR_LEN: usize = 200;
+/// How many times to try delivering an alert, and how long to wait in between
+const ALERT_ATTEMPTS: u32 = 2;
+const ALERT_RETRY_SECS: u64 = 5;
+/// How many redirects to follow at most, which also stops redirect loops
+const MAX_REDIRECTS: usize = 5;
+/// How many days before a target's certificate expires to start warning about it
+const CERT_WARN_DAYS: i64 = 14;
+/// How much of a response body is searched for the expected body, so huge bodies can't exhaust memory
+const MAX_BODY_LEN: usize = 64 * 1024;
+/// How much the pause between checks randomly varies, as a fraction of it, so deployments
+/// monitoring the same target don't all check it at the same moment. Set to 0 for a fixed pause.
+const JITTER_FRACTION: f64 = 0.2;
+/// The longest to wait before restarting the checks after they panicked
+const MAX_RESTART_BACKOFF_SECS: u64 = 60;
+/// How many targets are checked at the same time at most, so long lists of targets don't exhaust
+/// file descriptors. The other checks wait for their turn.
+pub(crate) const MAX_CONCURRENT_CHECKS: usize = 16;
+
+pub(crate) type HttpsClient = Client<CertConnector>;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Response {
+    clients_count: usize,
+    #[serde(rename = "dateTime")]
+    pub(crate) date_time: DateTime<Utc>,
+    /// Empty while the checks are paused
+    targets: Vec<TargetStatus>,
+    /// Whether the checks are paused, for maintenance on the targets
+    paused: bool,
+    /// The number of checks each target's `uptime_ratio` is computed over, at most
+    uptime_window: usize,
+    reason: Reason,
+}
+
+impl Response {
+    /// Whether this status was pushed because a recheck was asked for.
+    pub(crate) fn is_recheck(&self) -> bool {
+        matches!(self.reason, Reason::Recheck)
+    }
+
+    /// Narrows the status down to the named targets, or keeps all of them when none are named.
+    pub(crate) fn for_targets(self: &Arc<Self>, names: &HashSet<String>) -> Arc<Self> {
+        if names.is_empty() {
+            return self.clone();
+        }
+
+        let mut response = Self::clone(self);
+        response
+            .targets
+            .retain(|target| names.contains(&target.name));
+        Arc::new(response)
+    }
+}
+
+/// Why a status was pushed to the clients.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Reason {
+    /// A target went up or down, or responded with another status code
+    Change,
+    /// Nothing changed for a while
+    Heartbeat,
+    /// A recheck was asked for
+    Recheck,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct TargetStatus {
+    name: String,
+    /// Whether the target responded at all, only turning `false` after `FAILURE_THRESHOLD` failed
+    /// checks in a row, and only back to `true` once it recovered
+    is_up: bool,
+    state: TargetState,
+    /// Whether the target responded with the expected status code
+    healthy: bool,
+    url: String,
+    /// Where the redirects led, when any were followed
+    final_url: Option<String>,
+    /// Both are `None` when no response was received at all. `status_code` is always `None` for
+    /// TCP targets, with `latency_ms` being the time it took to connect.
+    status_code: Option<u16>,
+    latency_ms: Option<u64>,
+    /// The average and 95th percentile latency of the latest `UPTIME_WINDOW` checks that got a
+    /// response, or `None` before the first one did
+    latency_avg_ms: Option<u64>,
+    latency_p95_ms: Option<u64>,
+    /// Whether the check gave up after `TIMEOUT_SECS` without a response
+    timed_out: bool,
+    /// Why the latest check failed to get a response, if it did
+    last_error: Option<String>,
+    /// The fraction of the checks in the uptime window that found the target up
+    uptime_ratio: f64,
+    /// How many checks in a row failed to get a response
+    consecutive_failures: u32,
+    /// When `is_up` last flipped, or when the target was first checked
+    last_change: DateTime<Utc>,
+    /// How many days are left until the certificate of an HTTPS target expires. `None` for plain
+    /// HTTP targets, and when the check failed.
+    cert_expires_in_days: Option<i64>,
+}
+
+/// Whether a target is reported as up, as a string for the clients.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TargetState {
+    Up,
+    Down,
+    /// Was down and has passed every check since, but not for long enough to be up again
+    Recovering,
+}
+
+#[derive(Clone, Copy, Default)]
+enum Phase {
+    #[default]
+    Up,
+    Down,
+    Recovering {
+        since: Instant,
+    },
+}
+
+/// Keeps track of a target's results across checks.
+#[derive(Default)]
+struct TargetTracker {
+    results: VecDeque<bool>,
+    latencies: VecDeque<u64>,
+    consecutive_failures: u32,
+    phase: Phase,
+    /// How many times the target failed while recovering, since it was last up
+    failed_recoveries: u32,
+    /// The debounced `is_up` of the latest check, and since when it has held
+    last_change: Option<(bool, DateTime<Utc>)>,
+    cert_expires_in_days: Option<i64>,
+}
+
+impl TargetTracker {
+    /// Records the outcome of the latest check and fills in the stats derived from the earlier ones.
+    /// Returns whether the target went up or down, not counting its very first check.
+    fn record(&mut self, status: &mut TargetStatus, checked_at: DateTime<Utc>) -> bool {
+        self.results.push_back(status.is_up);
+        if self.results.len() > UPTIME_WINDOW {
+            self.results.pop_front();
+        }
+
+        let successes = self.results.iter().filter(|is_up| **is_up).count();
+        status.uptime_ratio = successes as f64 / self.results.len() as f64;
+
+        // Failed checks have no latency, so they are left out rather than skewing the stats
+        if let Some(latency_ms) = status.latency_ms {
+            self.latencies.push_back(latency_ms);
+            if self.latencies.len() > UPTIME_WINDOW {
+                self.latencies.pop_front();
+            }
+        }
+        if !self.latencies.is_empty() {
+            let mut sorted: Vec<_> = self.latencies.iter().copied().collect();
+            sorted.sort_unstable();
+            let p95_rank = (sorted.len() * 95).div_ceil(100);
+            status.latency_avg_ms = Some(sorted.iter().sum::<u64>() / sorted.len() as u64);
+            status.latency_p95_ms = Some(sorted[p95_rank - 1]);
+        }
+
+        let passed = status.is_up;
+        if passed {
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures += 1;
+        }
+        status.consecutive_failures = self.consecutive_failures;
+
+        // Debounce failures so a single transient one doesn't flip the target to down, and only
+        // flip it back up once it kept passing for the recovery period
+        self.phase = match (self.phase, passed) {
+            (Phase::Up, false) if self.consecutive_failures >= FAILURE_THRESHOLD => Phase::Down,
+            (Phase::Up, _) => Phase::Up,
+            (Phase::Down, true) => Phase::Recovering {
+                since: Instant::now(),
+            },
+            (Phase::Down, false) => Phase::Down,
+            (Phase::Recovering { since }, true) if since.elapsed() >= self.recovery_period() => {
+                self.failed_recoveries = 0;
+                Phase::Up
+            }
+            (phase @ Phase::Recovering { .. }, true) => phase,
+            (Phase::Recovering { .. }, false) => {
+                self.failed_recoveries += 1;
+                Phase::Down
+            }
+        };
+        status.is_up = matches!(self.phase, Phase::Up);
+        status.state = match self.phase {
+            Phase::Up => TargetState::Up,
+            Phase::Down => TargetState::Down,
+            Phase::Recovering { .. } => TargetState::Recovering,
+        };
+
+        let (last_change, flipped) = match self.last_change {
+            Some((was_up, since)) if was_up == status.is_up => (since, false),
+            Some(_) => (checked_at, true),
+            None => (checked_at, false),
+        };
+        self.last_change = Some((status.is_up, last_change));
+        status.last_change = last_change;
+
+        // Warn once a day rather than on every check
+        if let Some(days) = status.cert_expires_in_days {
+            if days < CERT_WARN_DAYS && self.cert_expires_in_days != Some(days) {
+                warn!(name = status.name, days, "certificate expires soon");
+            }
+        }
+        self.cert_expires_in_days = status.cert_expires_in_days;
+
+        flipped
+    }
+
+    fn recovery_period(&self) -> Duration {
+        let secs = RECOVERY_SECS.saturating_mul(1 << self.failed_recoveries.min(16));
+        Duration::from_secs(secs.min(MAX_RECOVERY_SECS))
+    }
+}
+
+/// How a target is checked.
+pub(crate) enum CheckKind {
+    /// With an HTTP request
+    Http(Uri),
+    /// Up as long as a TCP connection can be opened, for services that don't speak HTTP
+    Tcp(SocketAddr),
+}
+
+/// A target to monitor.
+pub(crate) struct Target {
+    pub(crate) name: String,
+    pub(crate) kind: CheckKind,
+    /// How long to pause between checks
+    pub(crate) interval: Duration,
+}
+
+/// Continually checks the targets and pushes their statuses to the clients.
+pub(crate) struct StatusChecker {
+    pub(crate) targets: Vec<Target>,
+    /// Shared by all checks, so connections are reused
+    pub(crate) client: HttpsClient,
+    /// Either `GET`, or `HEAD` to save bandwidth when only the status code matters
+    pub(crate) method: Method,
+    pub(crate) user_agent: HeaderValue,
+    /// The status code a healthy target responds with, or any 2xx code when `None`
+    pub(crate) expected_status: Option<u16>,
+    /// Text the body of a healthy target's response contains, when set. This never matches with
+    /// `HEAD`, since those responses have no body.
+    pub(crate) expected_body: Option<String>,
+    /// Whether to follow redirects, or to report the status of the first response
+    pub(crate) follow_redirects: bool,
+    /// Where to POST an alert whenever a target goes up or down
+    pub(crate) alert_webhook: Option<Uri>,
+    pub(crate) tx: watch::Sender<Option<Arc<Response>>>,
+    pub(crate) state: Arc<Mutex<State>>,
+    pub(crate) clients_count: Arc<AtomicUsize>,
+    pub(crate) pool: PgPool,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) shutdown: watch::Receiver<bool>,
+    /// Cuts the pause short to check right away, pushing the result even if nothing changed
+    pub(crate) recheck: Arc<Notify>,
+    /// Skips the checks while set, pushing a status without any targets instead
+    pub(crate) paused: Arc<AtomicBool>,
+    /// Holds a permit for each check in flight
+    pub(crate) checks: Semaphore,
+}
+
+impl StatusChecker {
+    /// Runs the checks until shutdown, restarting them with a backoff if they ever panic so the
+    /// statuses don't quietly go stale.
+    pub(crate) async fn supervise(self) {
+        let checker = Arc::new(self);
+        let mut shutdown = checker.shutdown.clone();
+        let max_backoff = Duration::from_secs(MAX_RESTART_BACKOFF_SECS);
+        let mut backoff = Duration::from_secs(1);
+
+        loop {
+            let started = Instant::now();
+            let Err(err) = tokio::spawn(checker.clone().run()).await else {
+                break;
+            };
+            if !err.is_panic() {
+                break;
+            }
+
+            // Checks that ran fine for a good while before panicking get to restart quickly again
+            if started.elapsed() > max_backoff {
+                backoff = Duration::from_secs(1);
+            }
+            error!(%err, ?backoff, "status checks panicked, restarting them");
+
+            tokio::select! {
+                _ = sleep(backoff) => {}
+                _ = shutdown.changed() => break,
+            }
+            backoff = (backoff * 2).min(max_backoff);
+        }
+    }
+
+    /// Merges the results of the targets, which are each checked on their own schedule, into the
+    /// statuses pushed to the clients.
+    async fn run(self: Arc<Self>) {
+        let mut shutdown = self.shutdown.clone();
+        let (results_tx, mut results_rx) = mpsc::channel(self.targets.len());
+        // Counts the rechecks, so a check that started before the latest one isn't taken for it
+        let (recheck_tx, _) = watch::channel(0);
+
+        // Dropping the set aborts the targets' tasks, so they are restarted along with this one
+        let mut tasks = JoinSet::new();
+        for index in 0..self.targets.len() {
+            let task = self
+                .clone()
+                .watch_target(index, results_tx.clone(), recheck_tx.subscribe());
+            tasks.spawn(task);
+        }
+        drop(results_tx);
+
+        let mut trackers: Vec<TargetTracker> = self
+            .targets
+            .iter()
+            .map(|_| TargetTracker::default())
+            .collect();
+        let mut latest: Vec<Option<TargetStatus>> = self.targets.iter().map(|_| None).collect();
+        // The targets that still have to report back since a recheck was asked for
+        let mut rechecking = vec![false; self.targets.len()];
+        let mut recheck_requested = false;
+
+        let heartbeat = Duration::from_secs(HEARTBEAT_SECS);
+        let mut last_sent = None;
+        let mut last_sent_at = Instant::now();
+        // Only updated when told about a pause or resume, so neither goes unnoticed
+        let mut paused = self.paused.load(Ordering::Relaxed);
+
+        loop {
+            // There is nothing to beat for until every target reported back, such as after a resume
+            let heartbeat_due =
+                last_sent.is_some() && (paused || latest.iter().all(Option::is_some));
+            let reason = tokio::select! {
+                Some((index, generation, mut status)) = results_rx.recv() => {
+                    // A check that was already running when the checks got paused
+                    if paused || self.paused.load(Ordering::Relaxed) {
+                        continue;
+                    }
+                    self.record(&mut trackers[index], &mut status).await;
+                    latest[index] = Some(status);
+
+                    if generation == *recheck_tx.borrow() {
+                        rechecking[index] = false;
+                    }
+                    if recheck_requested && !rechecking.contains(&true) {
+                        recheck_requested = false;
+                        Some(Reason::Recheck)
+                    } else {
+                        None
+                    }
+                }
+                _ = self.recheck.notified() => {
+                    let was_paused = std::mem::replace(&mut paused, self.paused.load(Ordering::Relaxed));
+                    // There is nothing to check while paused, but the clients still hear about it
+                    if paused {
+                        rechecking.fill(false);
+                        recheck_requested = false;
+                        Some(Reason::Recheck)
+                    } else {
+                        // The statuses from before the pause are stale, so wait for fresh ones
+                        if was_paused {
+                            latest.fill(None);
+                        }
+                        rechecking.fill(true);
+                        recheck_requested = true;
+                        recheck_tx.send_modify(|generation| *generation += 1);
+                        continue;
+                    }
+                }
+                _ = sleep(heartbeat.saturating_sub(last_sent_at.elapsed())), if heartbeat_due => None,
+                Some(Err(err)) = tasks.join_next() => match err.try_into_panic() {
+                    Ok(panic) => std::panic::resume_unwind(panic),
+                    Err(_) => continue,
+                },
+                _ = shutdown.changed() => break,
+            };
+
+            // Wait for every target's first check, so clients never see a partial status
+            let targets: Vec<TargetStatus> = if paused {
+                Vec::new()
+            } else {
+                match latest.iter().cloned().collect() {
+                    Some(targets) => targets,
+                    None => continue,
+                }
+            };
+
+            // Only push when something meaningful changed, or when a heartbeat is due
+            let summary: Vec<_> = targets.iter().map(|t| (t.is_up, t.status_code)).collect();
+            let summary = (paused, summary);
+            let reason = reason.or_else(|| {
+                if last_sent.as_ref() != Some(&summary) {
+                    Some(Reason::Change)
+                } else if last_sent_at.elapsed() >= heartbeat {
+                    Some(Reason::Heartbeat)
+                } else {
+                    None
+                }
+            });
+            let Some(reason) = reason else {
+                continue;
+            };
+
+            let response = Arc::new(Response {
+                clients_count: self.clients_count.load(Ordering::Relaxed),
+                date_time: Utc::now(),
+                targets,
+                paused,
+                uptime_window: UPTIME_WINDOW,
+                reason,
+            });
+
+            let mut state = self.state.lock().await;
+            state.history.push_back(response.clone());
+            if state.history.len() > HISTORY_CAPACITY {
+                state.history.pop_front();
+            }
+            drop(state);
+
+            if self.tx.send(Some(response)).is_err() {
+                break;
+            }
+            last_sent = Some(summary);
+            last_sent_at = Instant::now();
+        }
+    }
+
+    /// Checks a single target on its own schedule, so a slow target never holds up the others.
+    async fn watch_target(
+        self: Arc<Self>,
+        index: usize,
+        results_tx: mpsc::Sender<(usize, u64, TargetStatus)>,
+        mut recheck_rx: watch::Receiver<u64>,
+    ) {
+        let target = &self.targets[index];
+        let mut shutdown = self.shutdown.clone();
+
+        loop {
+            let started = Instant::now();
+            let generation = *recheck_rx.borrow_and_update();
+            if !self.paused.load(Ordering::Relaxed) {
+                let permit = self
+                    .checks
+                    .acquire()
+                    .await
+                    .expect("the semaphore is never closed");
+                let status = self.check_target(&target.name, &target.kind).await;
+                drop(permit);
+
+                // Waiting for a permit counts too, since the check falls behind either way
+                if started.elapsed() > target.interval {
+                    warn!(
+                        name = target.name,
+                        took = ?started.elapsed(),
+                        interval = ?target.interval,
+                        "the check took longer than its interval"
+                    );
+                }
+                if results_tx.send((index, generation, status)).await.is_err() {
+                    break;
+                }
+            }
+
+            // Account for the time spent checking to keep the checks on schedule
+            let jitter = rand::thread_rng().gen_range(-JITTER_FRACTION..=JITTER_FRACTION);
+            let pause = target.interval.mul_f64(1.0 + jitter);
+            tokio::select! {
+                _ = sleep(pause.saturating_sub(started.elapsed())) => {}
+                changed = recheck_rx.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+                _ = shutdown.changed() => break,
+            }
+        }
+    }
+
+    /// Records a target's latest check everywhere it is kept track of.
+    async fn record(&self, tracker: &mut TargetTracker, status: &mut TargetStatus) {
+        let checked_at = Utc::now();
+
+        // The metrics and the database see every failed check, before they are debounced
+        let passed = status.is_up;
+        self.metrics
+            .record_check(&status.name, passed, status.latency_ms);
+        let flipped = tracker.record(status, checked_at);
+
+        // Delivering the alert in the background keeps a slow webhook from stalling the checks
+        if let (true, Some(webhook)) = (flipped, &self.alert_webhook) {
+            let alert = json!({
+                "target": status.name,
+                "is_up": status.is_up,
+                "changed_at": status.last_change,
+                "status_code": status.status_code,
+            });
+            tokio::spawn(send_alert(
+                self.client.clone(),
+                webhook.clone(),
+                alert.to_string(),
+            ));
+        }
+
+        // Every check is stored, whether or not it gets pushed to the clients
+        if let Err(err) = store_check(&self.pool, status, passed, checked_at).await {
+            warn!(name = status.name, %err, "failed to store the check");
+        }
+    }
+
+    async fn check_target(&self, name: &str, kind: &CheckKind) -> TargetStatus {
+        match kind {
+            CheckKind::Http(uri) => self.check_http(name, uri).await,
+            CheckKind::Tcp(addr) => check_tcp(name, *addr).await,
+        }
+    }
+
+    async fn check_http(&self, name: &str, uri: &Uri) -> TargetStatus {
+        let start = Instant::now();
+        let resp = timeout(Duration::from_secs(TIMEOUT_SECS), self.send(uri)).await;
+        let timed_out = resp.is_err();
+        let (status_code, latency_ms, mut last_error, cert_expiry, body, final_url) = match resp {
+            Ok(Ok((resp, final_url, gave_up))) => {
+                let last_error =
+                    gave_up.then(|| format!("stopped following redirects after {MAX_REDIRECTS}"));
+                (
+                    Some(resp.status().as_u16()),
+                    Some(start.elapsed().as_millis() as u64),
+                    last_error,
+                    // Read off the connection the response came over, which is the final URL's
+                    resp.extensions().get::<CertExpiry>().copied(),
+                    Some(resp.into_body()),
+                    final_url.map(|uri| uri.to_string()),
+                )
+            }
+            Ok(Err(err)) => {
+                warn!(name, %uri, %err, "health check failed");
+                (
+                    None,
+                    None,
+                    Some(truncate_error(err.to_string())),
+                    None,
+                    None,
+                    None,
+                )
+            }
+            Err(_) => {
+                warn!(name, %uri, "health check timed out");
+                let err = format!("timed out after {TIMEOUT_SECS}s");
+                (None, None, Some(err), None, None, None)
+            }
+        };
+
+        // Reading the body shares the timeout with the request
+        let body_matches = match (&self.expected_body, body) {
+            (Some(expected), Some(body)) => {
+                let remaining = Duration::from_secs(TIMEOUT_SECS).saturating_sub(start.elapsed());
+                match timeout(remaining, read_body(body)).await {
+                    Ok(Ok(body)) => String::from_utf8_lossy(&body).contains(expected.as_str()),
+                    Ok(Err(err)) => {
+                        warn!(name, %uri, %err, "failed to read the health check body");
+                        last_error = Some(truncate_error(err.to_string()));
+                        false
+                    }
+                    Err(_) => {
+                        warn!(name, %uri, "reading the health check body timed out");
+                        last_error =
+                            Some(format!("timed out reading the body after {TIMEOUT_SECS}s"));
+                        false
+                    }
+                }
+            }
+            _ => true,
+        };
+
+        let healthy = body_matches
+            && status_code.is_some_and(|code| match self.expected_status {
+                Some(expected) => code == expected,
+                None => (200..=299).contains(&code),
+            });
+
+        let cert_expires_in_days = cert_expiry.map(|CertExpiry(expires_at)| {
+            (expires_at - Utc::now())
+                .num_seconds()
+                .div_euclid(24 * 60 * 60)
+        });
+
+        TargetStatus {
+            name: name.to_string(),
+            is_up: status_code.is_some(),
+            state: TargetState::Up,
+            healthy,
+            url: uri.to_string(),
+            final_url,
+            status_code,
+            latency_ms,
+            latency_avg_ms: None,
+            latency_p95_ms: None,
+            timed_out,
+            last_error,
+            uptime_ratio: 0.0,
+            consecutive_failures: 0,
+            last_change: Utc::now(),
+            cert_expires_in_days,
+        }
+    }
+
+    /// Requests the target, following up to `MAX_REDIRECTS` redirects when enabled. Returns the
+    /// last response, along with its URI if it was redirected to, and whether it is a redirect
+    /// that was left unfollowed because there were too many.
+    async fn send(
+        &self,
+        uri: &Uri,
+    ) -> Result<(hyper::Response<Body>, Option<Uri>, bool), hyper::Error> {
+        let mut uri = uri.clone();
+        let mut redirects = 0;
+
+        loop {
+            let request = Request::builder()
+                .method(self.method.clone())
+                .uri(uri.clone())
+                .header(header::USER_AGENT, self.user_agent.clone())
+                .body(Body::empty())
+                .expect("an already validated method, uri and header to build a request");
+            let resp = self.client.request(request).await?;
+            let final_url = (redirects > 0).then(|| uri.clone());
+
+            // A 304 isn't a redirect to follow, and neither is one without a usable `Location`
+            let status = resp.status();
+            let location = (self.follow_redirects
+                && status.is_redirection()
+                && status != StatusCode::NOT_MODIFIED)
+                .then(|| resp.headers().get(header::LOCATION))
+                .flatten()
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| redirect_target(&uri, location));
+            let Some(location) = location else {
+                return Ok((resp, final_url, false));
+            };
+            if redirects == MAX_REDIRECTS {
+                return Ok((resp, final_url, true));
+            }
+
+            uri = location;
+            redirects += 1;
+        }
+    }
+}
+
+/// POSTs an alert to the webhook, trying again once if that fails.
+async fn send_alert(client: HttpsClient, webhook: Uri, alert: String) {
+    for attempt in 1..=ALERT_ATTEMPTS {
+        if attempt > 1 {
+            sleep(Duration::from_secs(ALERT_RETRY_SECS)).await;
+        }
+
+        let request = Request::post(webhook.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(alert.clone()))
+            .expect("an already validated uri to build a request");
+        let resp = timeout(Duration::from_secs(TIMEOUT_SECS), client.request(request)).await;
+        match resp {
+            Ok(Ok(resp)) if resp.status().is_success() => return,
+            Ok(Ok(resp)) => {
+                warn!(%webhook, status = %resp.status(), attempt, "webhook rejected the alert")
+            }
+            Ok(Err(err)) => warn!(%webhook, %err, attempt, "failed to deliver the alert"),
+            Err(_) => warn!(%webhook, attempt, "delivering the alert timed out"),
+        }
+    }
+}
+
+/// Resolves where a redirect's `Location` leads, which is either an absolute URI, or a reference
+/// relative to the URI that redirected.
+fn redirect_target(from: &Uri, location: &str) -> Option<Uri> {
+    // Fragments never reach the server
+    let location = location.split('#').next()?;
+    if let Some(rest) = location.strip_prefix("//") {
+        return format!("{}://{rest}", from.scheme_str()?).parse().ok();
+    }
+    if !location.starts_with('/') {
+        if let Ok(uri) = location.parse::<Uri>() {
+            if uri.scheme().is_some() {
+                return Some(uri);
+            }
+        }
+    }
+
+    let (path, query) = match location.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (location, None),
+    };
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else if path.is_empty() {
+        from.path().to_string()
+    } else {
+        // A relative path replaces the last segment of the path that redirected
+        let base = &from.path()[..=from.path().rfind('/')?];
+        format!("{base}{path}")
+    };
+    let path = remove_dot_segments(&path);
+    let path_and_query = match query {
+        Some(query) => format!("{path}?{query}"),
+        None if location.is_empty() => from.path_and_query()?.to_string(),
+        None => path,
+    };
+
+    let mut parts = from.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Resolves the `.` and `..` segments of an absolute path.
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments = Vec::new();
+    for segment in path.split('/').skip(1) {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    // A trailing dot segment still names a directory
+    if path.ends_with("/.") || path.ends_with("/..") {
+        segments.push("");
+    }
+
+    format!("/{}", segments.join("/"))
+}
+
+async fn check_tcp(name: &str, addr: SocketAddr) -> TargetStatus {
+    let start = Instant::now();
+    let connect = timeout(Duration::from_secs(TIMEOUT_SECS), TcpStream::connect(addr)).await;
+    let timed_out = connect.is_err();
+    let (latency_ms, last_error) = match connect {
+        Ok(Ok(_)) => (Some(start.elapsed().as_millis() as u64), None),
+        Ok(Err(err)) => {
+            warn!(name, %addr, %err, "health check failed");
+            (None, Some(truncate_error(err.to_string())))
+        }
+        Err(_) => {
+            warn!(name, %addr, "health check timed out");
+            (None, Some(format!("timed out after {TIMEOUT_SECS}s")))
+        }
+    };
+
+    TargetStatus {
+        name: name.to_string(),
+        is_up: latency_ms.is_some(),
+        state: TargetState::Up,
+        healthy: latency_ms.is_some(),
+        url: format!("tcp://{addr}"),
+        final_url: None,
+        status_code: None,
+        latency_ms,
+        latency_avg_ms: None,
+        latency_p95_ms: None,
+        timed_out,
+        last_error,
+        uptime_ratio: 0.0,
+        consecutive_failures: 0,
+        last_change: Utc::now(),
+        cert_expires_in_days: None,
+    }
+}
+
+/// Reads up to `MAX_BODY_LEN` bytes of a body, ignoring the rest.
+async fn read_body(mut body: Body) -> Result<Vec<u8>, hyper::Error> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() >= MAX_BODY_LEN {
+            bytes.truncate(MAX_BODY_LEN);
+            break;
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn truncate_error(mut err: String) -> String {
+    if let Some((index, _)) = err.char_indices().nth(MAX_ERROR_LEN) {
+        err.truncate(index);
+        err.push('…');
+    }
+
+    err
+}
+
+async fn store_check(
+    pool: &PgPool,
+    status: &TargetStatus,
+    passed: bool,
+    checked_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO status_checks(name, checked_at, is_up, status_code, latency_ms) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(&status.name)
+    .bind(checked_at)
+    .bind(passed)
+    .bind(status.status_code.map(i32::from))
+    .bind(status.latency_ms.map(|latency_ms| latency_ms as i64))
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn response() -> Response {
+        Response {
+            clients_count: 2,
+            date_time: Utc::now(),
+            targets: vec![TargetStatus {
+                name: "api".to_string(),
+                is_up: true,
+                state: TargetState::Up,
+                healthy: true,
+                url: "https://api.shuttle.rs/".to_string(),
+                final_url: None,
+                status_code: Some(200),
+                latency_ms: Some(42),
+                latency_avg_ms: Some(40),
+                latency_p95_ms: Some(80),
+                timed_out: false,
+                last_error: None,
+                uptime_ratio: 1.0,
+                consecutive_failures: 0,
+                last_change: Utc::now(),
+                cert_expires_in_days: Some(90),
+            }],
+            paused: false,
+            uptime_window: UPTIME_WINDOW,
+            reason: Reason::Change,
+        }
+    }
+
+    #[test]
+    fn redirects_resolve_against_the_uri_that_redirected() {
+        let from: Uri = "https://example.com/app/home?tab=1".parse().unwrap();
+        let resolve = |location| redirect_target(&from, location).unwrap().to_string();
+
+        assert_eq!(resolve("https://other.example/"), "https://other.example/");
+        assert_eq!(resolve("//other.example/x"), "https://other.example/x");
+        assert_eq!(resolve("/login"), "https://example.com/login");
+        assert_eq!(resolve("login"), "https://example.com/app/login");
+        assert_eq!(
+            resolve("login?next=home"),
+            "https://example.com/app/login?next=home"
+        );
+        assert_eq!(resolve("./login"), "https://example.com/app/login");
+        assert_eq!(resolve("../login#top"), "https://example.com/login");
+        assert_eq!(resolve("?tab=2"), "https://example.com/app/home?tab=2");
+    }
+
+    #[test]
+    fn response_keeps_the_keys_clients_depend_on() {
+        let json = serde_json::to_value(response()).unwrap();
+
+        assert_eq!(json["clients_count"], 2);
+        let date_time = json["dateTime"].as_str().unwrap();
+        assert!(DateTime::parse_from_rfc3339(date_time).is_ok());
+        assert!(json.get("date_time").is_none());
+        assert_eq!(json["reason"], "change");
+        assert_eq!(json["targets"][0]["is_up"], Value::Bool(true));
+        assert_eq!(json["targets"][0]["status_code"], 200);
+    }
+
+    #[test]
+    fn response_round_trips() {
+        let json = serde_json::to_value(response()).unwrap();
+
+        let response: Response = serde_json::from_value(json.clone()).unwrap();
+
+        assert_eq!(serde_json::to_value(response).unwrap(), json);
+    }
+}
@@ -0,0 +1,51 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use tracing::error;
+
+/// An error returned by the REST routes, answered as `{"error":...,"detail":...}` JSON.
+#[derive(Debug)]
+pub(crate) enum AppError {
+    /// The request itself is wrong, in the way the detail says
+    BadRequest(String),
+    Unauthorized,
+    NotFound,
+    /// Something the request needs isn't there yet, or has run out
+    Unavailable(&'static str),
+    /// Logged, but not passed on to the client
+    Internal(anyhow::Error),
+}
+
+impl<E: Into<anyhow::Error>> From<E> for AppError {
+    fn from(err: E) -> Self {
+        Self::Internal(err.into())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, error, detail) = match &self {
+            Self::BadRequest(detail) => (StatusCode::BAD_REQUEST, "bad_request", detail.as_str()),
+            Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "a valid ?token= is required",
+            ),
+            Self::NotFound => (StatusCode::NOT_FOUND, "not_found", "nothing is here"),
+            Self::Unavailable(detail) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable", *detail),
+            Self::Internal(err) => {
+                error!("{err:#}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "internal",
+                    "something went wrong",
+                )
+            }
+        };
+
+        (status, Json(json!({ "error": error, "detail": detail }))).into_response()
+    }
+}
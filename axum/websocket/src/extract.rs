@@ -0,0 +1,25 @@
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use serde::de::DeserializeOwned;
+
+use crate::error::AppError;
+
+/// Like axum's `Query`, but rejects query strings that don't deserialize with the same JSON as the
+/// other errors, rather than with plain text.
+pub(crate) struct Query<T>(pub(crate) T);
+
+#[async_trait]
+impl<T, S> FromRequestParts<S> for Query<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Query(query) = axum::extract::Query::from_request_parts(parts, state)
+            .await
+            .map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+
+        Ok(Self(query))
+    }
+}
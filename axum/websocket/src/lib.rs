@@ -0,0 +1,1151 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context};
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        WebSocketUpgrade,
+    },
+    http::{header, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse,
+    },
+    routing::{get, get_service, post, MethodRouter},
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, Stream, StreamExt};
+use hyper::{header::HeaderValue, Client, Method, Uri};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use shuttle_runtime::CustomError;
+use shuttle_secrets::SecretStore;
+use sqlx::{FromRow, PgPool};
+use tokio::{
+    signal,
+    sync::{broadcast, mpsc, watch, Mutex, Notify, Semaphore},
+    time::{interval, sleep, timeout},
+};
+use tokio_stream::wrappers::WatchStream;
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowOrigin, CorsLayer},
+    services::{ServeDir, ServeFile},
+    trace::TraceLayer,
+};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+mod error;
+use error::AppError;
+
+mod extract;
+use extract::Query;
+
+mod cert;
+use cert::CertConnector;
+
+mod checker;
+use checker::{CheckKind, Response, StatusChecker, Target, MAX_CONCURRENT_CHECKS};
+
+mod metrics;
+use metrics::Metrics;
+
+pub(crate) struct State {
+    next_client_id: usize,
+    /// Holds `None` until the first status check completes
+    rx: watch::Receiver<Option<Arc<Response>>>,
+    chat_tx: broadcast::Sender<ChatMessage>,
+    pub(crate) history: VecDeque<Arc<Response>>,
+    /// Turns `true` once the server is shutting down
+    shutdown_rx: watch::Receiver<bool>,
+    /// Asks the status checks to check right away
+    recheck: Arc<Notify>,
+    /// Pauses the status checks while set
+    paused: Arc<AtomicBool>,
+}
+
+const PAUSE_SECS: u64 = 15;
+const STATUS_URI: &str = "https://api.shuttle.rs";
+const STATUS_NAME: &str = "api";
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const CHAT_CAPACITY: usize = 64;
+const REPLY_CAPACITY: usize = 8;
+/// How many clients can be connected to the WebSocket at the same time
+const MAX_CLIENTS: usize = 100;
+/// The largest message, and frame, a client may send. Clients sending larger ones are disconnected.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// How many messages a client may send per second, before being disconnected
+const RATE_LIMIT_PER_SEC: u32 = 10;
+/// How many of the latest statuses to replay to newly connected clients
+pub(crate) const HISTORY_CAPACITY: usize = 60;
+/// How often to ping clients. Clients that don't answer `MAX_MISSED_PINGS` pings in a row are
+/// disconnected, so connections that silently dropped don't linger.
+const PING_INTERVAL_SECS: u64 = 30;
+const MAX_MISSED_PINGS: u32 = 2;
+/// How long clients get to receive their close frames when the server shuts down
+const SHUTDOWN_GRACE_SECS: u64 = 1;
+/// How far back `/history` looks by default, and at most, in hours
+const HISTORY_HOURS: u32 = 1;
+const MAX_HISTORY_HOURS: u32 = 24 * 7;
+/// The most statuses a single `/history/range` query returns, keeping the latest ones
+const MAX_RANGE_LEN: usize = 30;
+/// Served in place of the status page when it is missing from the static folder
+const MISSING_STATIC_PAGE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+  <head><meta charset=\"utf-8\" /><title>Status page missing</title></head>
+  <body>
+    <h1>The status page is missing</h1>
+    <p>No <code>index.html</code> was found in the static folder, which should be deployed along
+    with this service. The API still works: see <a href=\"/status\">/status</a>, or connect to
+    <code>/websocket</code>.</p>
+  </body>
+</html>
+";
+/// How long `/poll` waits for a new status before answering with none
+const POLL_TIMEOUT_SECS: u64 = 25;
+/// How long `/recheck` waits for the fresh status, a bit longer than a check can take
+const RECHECK_WAIT_SECS: u64 = checker::TIMEOUT_SECS + 5;
+
+/// A chat message published by one client, to be forwarded to every other client.
+#[derive(Clone)]
+struct ChatMessage {
+    client_id: usize,
+    body: String,
+}
+
+/// A message sent by a client, as JSON tagged with an `action`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ClientMessage {
+    /// Only receive the statuses of these targets, or of all of them when empty
+    Subscribe { targets: HashSet<String> },
+    /// Answered with a `pong`, for clients that can't send WebSocket pings themselves
+    Ping,
+    /// Check the targets right away
+    Recheck,
+    /// Forwarded to all other clients
+    Chat { body: String },
+    /// An action this version doesn't know about, which is ignored
+    #[serde(other)]
+    Unknown,
+}
+
+/// The version of the payloads sent to clients, bumped whenever they change incompatibly
+const PAYLOAD_VERSION: u32 = 1;
+
+/// A payload along with its version, which is how every payload is sent.
+#[derive(Serialize)]
+struct Versioned<P> {
+    v: u32,
+    #[serde(flatten)]
+    payload: P,
+}
+
+/// Everything sent to clients is tagged with a `type` so they can tell the payloads apart.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Payload<'a> {
+    /// Sent first on every connection, so clients and logs can tell connections apart
+    Welcome {
+        client_id: usize,
+    },
+    Status(&'a Response),
+    Chat {
+        body: &'a str,
+    },
+    History {
+        items: &'a [Arc<Response>],
+    },
+    /// The statuses in a time range, which are only the latest ones when `truncated` is set
+    Range {
+        items: Vec<Arc<Response>>,
+        truncated: bool,
+    },
+    Pong,
+}
+
+impl Payload<'_> {
+    fn versioned(self) -> Versioned<Self> {
+        Versioned {
+            v: PAYLOAD_VERSION,
+            payload: self,
+        }
+    }
+}
+
+/// How payloads are encoded for a client, chosen with `?format=` when it connects.
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// JSON in text frames
+    #[default]
+    Json,
+    /// MessagePack in binary frames, for bandwidth sensitive clients
+    Msgpack,
+}
+
+impl Format {
+    fn encode(self, payload: Payload) -> Message {
+        match self {
+            Format::Json => Message::Text(serde_json::to_string(&payload.versioned()).unwrap()),
+            Format::Msgpack => {
+                Message::Binary(rmp_serde::to_vec_named(&payload.versioned()).unwrap())
+            }
+        }
+    }
+}
+
+/// The optional token clients need to present, loaded from the `AUTH_TOKEN` secret.
+#[derive(Clone)]
+struct AuthToken(Option<Arc<str>>);
+
+impl AuthToken {
+    /// Checks the given token, letting everyone in when no token is configured.
+    fn verify(&self, token: Option<&str>) -> bool {
+        match (&self.0, token) {
+            (None, _) => true,
+            (Some(expected), Some(token)) => {
+                constant_time_eq(expected.as_bytes(), token.as_bytes())
+            }
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// A reserved place among the `MAX_CLIENTS` connections, given back when dropped.
+struct ClientSlot(Arc<AtomicUsize>);
+
+impl ClientSlot {
+    /// Counts a new client, unless the limit has been reached already.
+    fn acquire(clients_count: Arc<AtomicUsize>) -> Option<Self> {
+        // Checking and incrementing in one step, so concurrent connections can't slip past the limit
+        let mut current = clients_count.load(Ordering::Relaxed);
+        loop {
+            if current >= MAX_CLIENTS {
+                return None;
+            }
+
+            match clients_count.compare_exchange_weak(
+                current,
+                current + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(Self(clients_count)),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A token bucket holding up to `RATE_LIMIT_PER_SEC` tokens, which refills continuously.
+struct RateLimiter {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        Self {
+            tokens: RATE_LIMIT_PER_SEC as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token for a message, returning `false` when the bucket is empty.
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * RATE_LIMIT_PER_SEC as f64;
+        self.tokens = (self.tokens + refill).min(RATE_LIMIT_PER_SEC as f64);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[derive(Deserialize)]
+struct AuthParams {
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    hours: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct RangeParams {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+/// A single check of a target, as stored in the database.
+#[derive(Serialize, FromRow)]
+struct StatusCheck {
+    id: i64,
+    name: String,
+    checked_at: DateTime<Utc>,
+    is_up: bool,
+    status_code: Option<i32>,
+    latency_ms: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct PollParams {
+    since: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct FormatParams {
+    #[serde(default)]
+    format: Format,
+}
+
+/// Builds the status page and its API, and starts checking the targets. The database is expected
+/// to already have the tables from `schema.sql`.
+pub async fn app(
+    static_folder: PathBuf,
+    secrets: SecretStore,
+    pool: PgPool,
+) -> Result<Router, shuttle_runtime::Error> {
+    // All settings are optional, falling back to the defaults above when they are not set.
+    let pause_secs = match secrets.get("CHECK_INTERVAL_SECS") {
+        Some(secs) => match secs.parse() {
+            Ok(0) => return Err(anyhow!("CHECK_INTERVAL_SECS has to be at least 1").into()),
+            Ok(secs) => secs,
+            Err(err) => return Err(anyhow!("invalid CHECK_INTERVAL_SECS {secs:?}: {err}").into()),
+        },
+        None => PAUSE_SECS,
+    };
+    let targets = parse_targets(&secrets)?;
+    let targets = parse_intervals(&secrets, targets, Duration::from_secs(pause_secs))?;
+    let method = match secrets.get("CHECK_METHOD") {
+        Some(method) => match method.to_uppercase().as_str() {
+            "GET" => Method::GET,
+            "HEAD" => Method::HEAD,
+            _ => return Err(anyhow!("CHECK_METHOD {method:?} is neither GET nor HEAD").into()),
+        },
+        None => Method::GET,
+    };
+    let user_agent = secrets
+        .get("CHECK_USER_AGENT")
+        .unwrap_or_else(|| USER_AGENT.to_string());
+    let user_agent = HeaderValue::from_str(&user_agent)
+        .map_err(|err| anyhow!("invalid CHECK_USER_AGENT {user_agent:?}: {err}"))?;
+    let expected_status = match secrets.get("EXPECTED_STATUS") {
+        Some(status) => Some(
+            status
+                .parse()
+                .map_err(|err| anyhow!("invalid EXPECTED_STATUS {status:?}: {err}"))?,
+        ),
+        None => None,
+    };
+    let expected_body = secrets.get("EXPECTED_BODY");
+    let alert_webhook = match secrets.get("ALERT_WEBHOOK_URL") {
+        Some(webhook) => Some(parse_uri("ALERT_WEBHOOK_URL", &webhook)?),
+        None => None,
+    };
+    let follow_redirects = match secrets.get("FOLLOW_REDIRECTS") {
+        Some(follow) => follow
+            .parse()
+            .map_err(|err| anyhow!("invalid FOLLOW_REDIRECTS {follow:?}: {err}"))?,
+        None => false,
+    };
+    let max_concurrent_checks = match secrets.get("MAX_CONCURRENT_CHECKS") {
+        Some(max) => match max.parse() {
+            Ok(0) => return Err(anyhow!("MAX_CONCURRENT_CHECKS has to be at least 1").into()),
+            Ok(max) => max,
+            Err(err) => return Err(anyhow!("invalid MAX_CONCURRENT_CHECKS {max:?}: {err}").into()),
+        },
+        None => MAX_CONCURRENT_CHECKS,
+    };
+    let metrics = Arc::new(Metrics::new().map_err(CustomError::new)?);
+    let auth_token = AuthToken(secrets.get("AUTH_TOKEN").map(Into::into));
+    let cors = parse_cors(secrets.get("CORS_ORIGINS"))?;
+
+    let (tx, rx) = watch::channel(None);
+    let (chat_tx, _) = broadcast::channel(CHAT_CAPACITY);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(shutdown_on_signal(shutdown_tx));
+    let recheck = Arc::new(Notify::new());
+    let paused = Arc::new(AtomicBool::new(false));
+
+    let state = Arc::new(Mutex::new(State {
+        next_client_id: 0,
+        rx,
+        chat_tx,
+        history: VecDeque::with_capacity(HISTORY_CAPACITY + 1),
+        shutdown_rx: shutdown_rx.clone(),
+        recheck: recheck.clone(),
+        paused: paused.clone(),
+    }));
+
+    // The number of connected clients is kept outside of the lock, since it is updated so often
+    let clients_count = Arc::new(AtomicUsize::new(0));
+
+    // Spawn a thread to continually check the status of the api
+    let checker = StatusChecker {
+        targets,
+        client: Client::builder().build(CertConnector::new()),
+        method,
+        user_agent,
+        expected_status,
+        expected_body,
+        follow_redirects,
+        alert_webhook,
+        tx,
+        state: state.clone(),
+        clients_count: clients_count.clone(),
+        pool: pool.clone(),
+        metrics: metrics.clone(),
+        shutdown: shutdown_rx,
+        recheck,
+        paused,
+        checks: Semaphore::new(max_concurrent_checks),
+    };
+    tokio::spawn(checker.supervise());
+
+    let router = Router::new()
+        .route("/websocket", get(websocket_handler))
+        .route("/status", get(status_handler))
+        .route("/recheck", post(recheck_handler))
+        .route("/admin/pause", post(pause_handler))
+        .route("/admin/resume", post(resume_handler))
+        .route("/events", get(events_handler))
+        .route("/poll", get(poll_handler))
+        .route("/health", get(health_handler))
+        .route("/history", get(history_handler))
+        .route("/history/range", get(history_range_handler))
+        .route("/metrics", get(metrics_handler))
+        .fallback_service(static_files(static_folder))
+        .layer(Extension(state))
+        .layer(Extension(clients_count))
+        .layer(Extension(auth_token))
+        .layer(Extension(pool))
+        .layer(Extension(metrics))
+        .layer(cors)
+        .layer(TraceLayer::new_for_http());
+
+    Ok(router)
+}
+
+/// Serves the status page and its assets, compressed when the browser accepts it. Only the static
+/// files are compressed, leaving the WebSocket upgrade and the event stream alone.
+///
+/// Paths that match no file get `index.html`, so a frontend router can handle deep links. The API
+/// routes are matched before this fallback is ever reached.
+fn static_files(static_folder: PathBuf) -> MethodRouter {
+    let index_path = static_folder.join("index.html");
+    if !index_path.is_file() {
+        warn!(
+            ?static_folder,
+            "no index.html in the static folder, serving a placeholder"
+        );
+        return get(|| async { Html(MISSING_STATIC_PAGE) });
+    }
+    let index = ServeFile::new(index_path);
+
+    get_service(ServeDir::new(static_folder).fallback(index))
+        .handle_error(handle_error)
+        .layer(CompressionLayer::new())
+}
+
+/// Reads the targets to monitor from the `STATUS_TARGETS` secret, given as comma separated
+/// `name=uri` pairs. Falls back to a single target using the `STATUS_URI` secret.
+fn parse_targets(secrets: &SecretStore) -> Result<Vec<(String, CheckKind)>, anyhow::Error> {
+    let Some(targets) = secrets.get("STATUS_TARGETS") else {
+        let status_uri = secrets
+            .get("STATUS_URI")
+            .unwrap_or_else(|| STATUS_URI.to_string());
+        let kind = parse_check("STATUS_URI", &status_uri)?;
+
+        return Ok(vec![(STATUS_NAME.to_string(), kind)]);
+    };
+
+    targets
+        .split(',')
+        .map(|target| {
+            let (name, uri) = target
+                .split_once('=')
+                .ok_or_else(|| anyhow!("STATUS_TARGETS entry {target:?} is not a name=uri pair"))?;
+            let kind = parse_check("STATUS_TARGETS uri", uri.trim())?;
+
+            Ok((name.trim().to_string(), kind))
+        })
+        .collect()
+}
+
+/// Gives each target its own check interval, from the comma separated `name=secs` pairs in the
+/// `TARGET_INTERVALS` secret. Targets it doesn't mention are checked every `default`.
+fn parse_intervals(
+    secrets: &SecretStore,
+    targets: Vec<(String, CheckKind)>,
+    default: Duration,
+) -> Result<Vec<Target>, anyhow::Error> {
+    let mut targets: Vec<_> = targets
+        .into_iter()
+        .map(|(name, kind)| Target {
+            name,
+            kind,
+            interval: default,
+        })
+        .collect();
+    let Some(intervals) = secrets.get("TARGET_INTERVALS") else {
+        return Ok(targets);
+    };
+
+    for entry in intervals.split(',') {
+        let (name, secs) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("TARGET_INTERVALS entry {entry:?} is not a name=secs pair"))?;
+        let (name, secs) = (name.trim(), secs.trim());
+        let secs: u64 = secs
+            .parse()
+            .map_err(|err| anyhow!("invalid TARGET_INTERVALS secs {secs:?}: {err}"))?;
+        if secs == 0 {
+            return Err(anyhow!(
+                "TARGET_INTERVALS secs for {name:?} has to be at least 1"
+            ));
+        }
+        let target = targets
+            .iter_mut()
+            .find(|target| target.name == name)
+            .ok_or_else(|| anyhow!("TARGET_INTERVALS names an unknown target {name:?}"))?;
+        target.interval = Duration::from_secs(secs);
+    }
+
+    Ok(targets)
+}
+
+/// Builds the CORS layer from the comma separated origins in the `CORS_ORIGINS` secret, where `*`
+/// allows any origin. Only same origin requests are allowed when it is not set. Browsers don't apply
+/// CORS to WebSockets, so this only affects the REST routes.
+fn parse_cors(origins: Option<String>) -> Result<CorsLayer, anyhow::Error> {
+    let Some(origins) = origins else {
+        return Ok(CorsLayer::new());
+    };
+
+    let allow_origin = if origins.trim() == "*" {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .split(',')
+            .map(|origin| {
+                let origin = origin.trim();
+                HeaderValue::from_str(origin)
+                    .map_err(|err| anyhow!("invalid CORS_ORIGINS origin {origin:?}: {err}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        AllowOrigin::list(origins)
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST]))
+}
+
+/// Parses how to check a target: `tcp://ip:port` to only open a TCP connection, or otherwise a URI
+/// to send HTTP requests to.
+fn parse_check(key: &str, uri: &str) -> Result<CheckKind, anyhow::Error> {
+    match uri.strip_prefix("tcp://") {
+        Some(addr) => {
+            let addr = addr
+                .parse()
+                .map_err(|err| anyhow!("invalid {key} {uri:?}, expected tcp://ip:port: {err}"))?;
+            Ok(CheckKind::Tcp(addr))
+        }
+        None => Ok(CheckKind::Http(parse_uri(key, uri)?)),
+    }
+}
+
+/// Parses a URI to check, which needs an http(s) scheme and a host for the checks to get anywhere.
+fn parse_uri(key: &str, uri: &str) -> Result<Uri, anyhow::Error> {
+    let parsed: Uri = uri
+        .parse()
+        .map_err(|err| anyhow!("invalid {key} {uri:?}: {err}"))?;
+
+    match (parsed.scheme_str(), parsed.host()) {
+        (Some("http" | "https"), Some(_)) => Ok(parsed),
+        _ => Err(anyhow!(
+            "invalid {key} {uri:?}: expected an http:// or https:// URL with a host"
+        )),
+    }
+}
+
+/// Tells the status checks and the WebSocket clients to stop once the process is asked to shut
+/// down, and then exits.
+async fn shutdown_on_signal(shutdown_tx: watch::Sender<bool>) {
+    // A handler that fails to install just never fires, rather than being mistaken for a shutdown
+    let ctrl_c = async {
+        if signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    let _ = shutdown_tx.send(true);
+
+    // Handling the signals replaced the default of exiting right away, so exit ourselves once
+    // clients had a moment to receive their close frames
+    sleep(Duration::from_secs(SHUTDOWN_GRACE_SECS)).await;
+    std::process::exit(0);
+}
+
+/// Compares in constant time, so the time taken does not reveal how much of a token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn handle_error(err: std::io::Error) -> AppError {
+    match err.kind() {
+        io::ErrorKind::NotFound => AppError::NotFound,
+        _ => AppError::Internal(anyhow!(err).context("failed to serve a static file")),
+    }
+}
+
+/// Returns the same status the WebSocket streams, for clients that just want a snapshot.
+async fn status_handler(
+    Extension(state): Extension<Arc<Mutex<State>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let response = state.lock().await.rx.borrow().clone();
+    let response = response.ok_or(AppError::Unavailable("the targets are still being checked"))?;
+
+    let json = serde_json::to_string(&Payload::Status(&response).versioned())?;
+    Ok(([(header::CONTENT_TYPE, "application/json")], json))
+}
+
+/// Long polls for the statuses, for clients that can use neither the WebSocket nor Server-Sent
+/// Events. Returns the latest status right away if it is newer than `since`, and otherwise waits
+/// for the next one. Answers `204 No Content` when none came in time, for the client to poll again.
+async fn poll_handler(
+    Query(PollParams { since }): Query<PollParams>,
+    Extension(state): Extension<Arc<Mutex<State>>>,
+) -> impl IntoResponse {
+    let mut rx = state.lock().await.rx.clone();
+
+    let response = rx.borrow_and_update().clone();
+    if let Some(response) = response {
+        let newer = match since {
+            Some(since) => response.date_time > since,
+            None => true,
+        };
+        if newer {
+            return Json(Payload::Status(&response).versioned()).into_response();
+        }
+    }
+
+    let wait = Duration::from_secs(POLL_TIMEOUT_SECS);
+    if !matches!(timeout(wait, rx.changed()).await, Ok(Ok(()))) {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+
+    let response = rx.borrow().clone();
+    match response {
+        Some(response) => Json(Payload::Status(&response).versioned()).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Checks the targets right away and returns the fresh status, guarded by the same token as the
+/// WebSocket. Answers `202 Accepted` if the check takes too long to wait for.
+async fn recheck_handler(
+    Query(params): Query<AuthParams>,
+    Extension(state): Extension<Arc<Mutex<State>>>,
+    Extension(auth_token): Extension<AuthToken>,
+) -> Result<axum::response::Response, AppError> {
+    if !auth_token.verify(params.token.as_deref()) {
+        return Err(AppError::Unauthorized);
+    }
+
+    let (mut rx, recheck) = {
+        let state = state.lock().await;
+        (state.rx.clone(), state.recheck.clone())
+    };
+    // Only the status pushed for a recheck asked for after this counts as fresh, not a change or
+    // heartbeat that happens to come first
+    rx.borrow_and_update();
+    recheck.notify_one();
+    let fresh = async {
+        loop {
+            rx.changed().await.ok()?;
+            let response = rx.borrow_and_update().clone();
+            if let Some(response) = response.filter(|response| response.is_recheck()) {
+                return Some(response);
+            }
+        }
+    };
+
+    let wait = Duration::from_secs(RECHECK_WAIT_SECS);
+    Ok(match timeout(wait, fresh).await {
+        Ok(Some(response)) => Json(Payload::Status(&response).versioned()).into_response(),
+        _ => StatusCode::ACCEPTED.into_response(),
+    })
+}
+
+/// Pauses the checks, for maintenance on the targets, guarded by the same token as the WebSocket.
+async fn pause_handler(
+    Query(params): Query<AuthParams>,
+    Extension(state): Extension<Arc<Mutex<State>>>,
+    Extension(auth_token): Extension<AuthToken>,
+) -> Result<StatusCode, AppError> {
+    set_paused(params, state, auth_token, true).await
+}
+
+/// Resumes the checks, guarded by the same token as the WebSocket.
+async fn resume_handler(
+    Query(params): Query<AuthParams>,
+    Extension(state): Extension<Arc<Mutex<State>>>,
+    Extension(auth_token): Extension<AuthToken>,
+) -> Result<StatusCode, AppError> {
+    set_paused(params, state, auth_token, false).await
+}
+
+async fn set_paused(
+    params: AuthParams,
+    state: Arc<Mutex<State>>,
+    auth_token: AuthToken,
+    paused: bool,
+) -> Result<StatusCode, AppError> {
+    if !auth_token.verify(params.token.as_deref()) {
+        return Err(AppError::Unauthorized);
+    }
+
+    let state = state.lock().await;
+    state.paused.store(paused, Ordering::Relaxed);
+    // Let the clients know right away, rather than at the next check
+    state.recheck.notify_one();
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Streams the same statuses as the WebSocket as Server-Sent Events, starting with the latest one.
+async fn events_handler(
+    Extension(state): Extension<Arc<Mutex<State>>>,
+) -> Sse<impl Stream<Item = Result<Event, serde_json::Error>>> {
+    let rx = state.lock().await.rx.clone();
+
+    let stream = WatchStream::new(rx)
+        .filter_map(|response| async move { response })
+        .map(|response| Event::default().json_data(Payload::Status(&response).versioned()));
+
+    Sse::new(stream).keep_alive(KeepAlive::new())
+}
+
+/// A cheap liveness probe for this service itself, which never calls out to the targets.
+async fn health_handler(
+    Extension(clients_count): Extension<Arc<AtomicUsize>>,
+) -> impl IntoResponse {
+    let clients = clients_count.load(Ordering::Relaxed);
+
+    Json(json!({ "status": "ok", "clients": clients }))
+}
+
+/// Returns the stored checks of the last `?hours=` hours, oldest first.
+async fn history_handler(
+    Query(params): Query<HistoryParams>,
+    Extension(pool): Extension<PgPool>,
+) -> Result<Json<Vec<StatusCheck>>, AppError> {
+    let hours = params.hours.unwrap_or(HISTORY_HOURS).min(MAX_HISTORY_HOURS);
+
+    let checks = sqlx::query_as(
+        "SELECT * FROM status_checks WHERE checked_at > now() - make_interval(hours => $1) ORDER BY checked_at",
+    )
+    .bind(hours as i32)
+    .fetch_all(&pool)
+    .await
+    .context("failed to fetch the history")?;
+
+    Ok(Json(checks))
+}
+
+/// The recent statuses pushed between `from` and `to`, from the history kept in memory.
+async fn history_range_handler(
+    Query(params): Query<RangeParams>,
+    Extension(state): Extension<Arc<Mutex<State>>>,
+) -> Result<Json<Versioned<Payload<'static>>>, AppError> {
+    if params.from > params.to {
+        return Err(AppError::BadRequest("from is after to".into()));
+    }
+
+    let state = state.lock().await;
+    let mut matching = state
+        .history
+        .iter()
+        .rev()
+        .filter(|response| (params.from..=params.to).contains(&response.date_time));
+    let mut items: Vec<_> = matching.by_ref().take(MAX_RANGE_LEN).cloned().collect();
+    let truncated = matching.next().is_some();
+    drop(state);
+    items.reverse();
+
+    Ok(Json(Payload::Range { items, truncated }.versioned()))
+}
+
+async fn metrics_handler(
+    Extension(metrics): Extension<Arc<Metrics>>,
+) -> Result<impl IntoResponse, AppError> {
+    let text = metrics.encode().context("failed to encode the metrics")?;
+
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text))
+}
+
+async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    Query(params): Query<AuthParams>,
+    Query(FormatParams { format }): Query<FormatParams>,
+    Extension(state): Extension<Arc<Mutex<State>>>,
+    Extension(clients_count): Extension<Arc<AtomicUsize>>,
+    Extension(auth_token): Extension<AuthToken>,
+    Extension(metrics): Extension<Arc<Metrics>>,
+) -> impl IntoResponse {
+    if !auth_token.verify(params.token.as_deref()) {
+        return AppError::Unauthorized.into_response();
+    }
+
+    // Reserving the slot before upgrading means it is given back even if the upgrade fails
+    let Some(slot) = ClientSlot::acquire(clients_count) else {
+        return AppError::Unavailable("too many clients are connected").into_response();
+    };
+
+    ws.max_message_size(MAX_MESSAGE_SIZE)
+        .max_frame_size(MAX_MESSAGE_SIZE)
+        .on_upgrade(move |socket| websocket(socket, state, slot, format, metrics))
+}
+
+async fn websocket(
+    stream: WebSocket,
+    state: Arc<Mutex<State>>,
+    slot: ClientSlot,
+    format: Format,
+    metrics: Arc<Metrics>,
+) {
+    // By splitting we can send and receive at the same time.
+    let (mut sender, mut receiver) = stream.split();
+
+    let (client_id, mut rx, chat_tx, mut chat_rx, history, mut shutdown, recheck) = {
+        let mut state = state.lock().await;
+        state.next_client_id += 1;
+
+        let history: Vec<_> = state.history.iter().cloned().collect();
+
+        (
+            state.next_client_id,
+            state.rx.clone(),
+            state.chat_tx.clone(),
+            state.chat_tx.subscribe(),
+            history,
+            state.shutdown_rx.clone(),
+            state.recheck.clone(),
+        )
+    };
+    // Counting up and down rather than setting the count, which concurrent clients could reorder
+    metrics.clients_connected.inc();
+    let clients_count = slot.0.load(Ordering::Relaxed);
+    info!(client_id, clients_count, "client connected");
+
+    // Lets the receiving task send frames of its own, like a close frame, through the sending task.
+    // The sending task stops once the receiving task drops its end.
+    let (reply_tx, mut reply_rx) = mpsc::channel(REPLY_CAPACITY);
+    // Lets the receiving task tell the sending task whenever the client answers a ping
+    let (pong_tx, mut pong_rx) = watch::channel(());
+    // The targets this client subscribed to, set by the receiving task
+    let subscription = Arc::new(Mutex::new(HashSet::new()));
+    let send_subscription = subscription.clone();
+    let recv_rx = rx.clone();
+
+    // This task will receive watch, chat and reply messages and forward them to this connected client.
+    // The chat receiver is dropped with the task, which unsubscribes it from the broadcast.
+    let mut send_shutdown = shutdown.clone();
+    let mut send_task = tokio::spawn(
+        async move {
+            let msg = format.encode(Payload::Welcome { client_id });
+            if sender.send(msg).await.is_err() {
+                return;
+            }
+
+            // Replay the recent statuses so the client does not start with an empty page
+            if !history.is_empty() {
+                let msg = format.encode(Payload::History { items: &history });
+                if sender.send(msg).await.is_err() {
+                    return;
+                }
+            }
+
+            // Send the latest status right away instead of waiting for the next check
+            let response = rx.borrow_and_update().clone();
+            if let Some(response) = response {
+                let msg = format.encode(Payload::Status(&response));
+                if sender.send(msg).await.is_err() {
+                    return;
+                }
+            }
+
+            let mut ping = interval(Duration::from_secs(PING_INTERVAL_SECS));
+            // The first tick completes right away, but the client only needs pinging after a while
+            ping.tick().await;
+            let mut missed_pings = 0;
+
+            loop {
+                let msg = tokio::select! {
+                    _ = ping.tick() => {
+                        // Any pong since the last ping answers it
+                        if pong_rx.has_changed().unwrap_or(false) {
+                            pong_rx.borrow_and_update();
+                            missed_pings = 0;
+                        }
+                        if missed_pings == MAX_MISSED_PINGS {
+                            warn!("client stopped answering pings");
+                            break;
+                        }
+                        missed_pings += 1;
+                        Message::Ping(Vec::new())
+                    }
+                    changed = rx.changed() => {
+                        if changed.is_err() {
+                            break;
+                        }
+                        let response = rx.borrow().clone();
+                        let Some(response) = response else {
+                            continue;
+                        };
+                        let response = response.for_targets(&*send_subscription.lock().await);
+                        format.encode(Payload::Status(&response))
+                    }
+                    chat = chat_rx.recv() => match chat {
+                        // Don't echo a client's own messages back to it
+                        Ok(chat) if chat.client_id == client_id => continue,
+                        Ok(chat) => format.encode(Payload::Chat { body: &chat.body }),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    reply = reply_rx.recv() => match reply {
+                        Some(reply) => reply,
+                        None => break,
+                    },
+                    // Give the client a cue to reconnect, rather than just dropping the connection
+                    _ = send_shutdown.changed() => Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "going away".into(),
+                    })),
+                };
+
+                let is_close = matches!(msg, Message::Close(_));
+                if sender.send(msg).await.is_err() || is_close {
+                    break;
+                }
+            }
+        }
+        .instrument(info_span!("send_task", client_id)),
+    );
+
+    // This task will receive messages from this client and publish them to all other clients.
+    let mut recv_task = tokio::spawn(
+        async move {
+            let mut rate_limiter = RateLimiter::new();
+
+            while let Some(msg) = receiver.next().await {
+                let msg = match msg {
+                    Ok(msg) => msg,
+                    // Such as a message over `MAX_MESSAGE_SIZE`, so let the client know why it is dropped
+                    Err(err) => {
+                        debug!(%err, "failed to receive from client");
+                        let _ = reply_tx
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::PROTOCOL,
+                                reason: "invalid or oversized message".into(),
+                            })))
+                            .await;
+                        break;
+                    }
+                };
+                debug!(?msg, "received message");
+
+                if !rate_limiter.try_take() {
+                    warn!("client exceeded the rate limit");
+                    let _ = reply_tx
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::POLICY,
+                            reason: "rate limit exceeded".into(),
+                        })))
+                        .await;
+                    break;
+                }
+
+                match msg {
+                    Message::Text(text) => match serde_json::from_str(&text) {
+                        Ok(ClientMessage::Subscribe { targets }) => {
+                            // Narrow down the pushes first, so none slips through unfiltered after
+                            // the reply below
+                            *subscription.lock().await = targets.clone();
+
+                            // Send the narrowed down status right away, rather than at the next push
+                            let response = recv_rx.borrow().clone();
+                            if let Some(response) = response {
+                                let response = response.for_targets(&targets);
+                                let _ = reply_tx
+                                    .send(format.encode(Payload::Status(&response)))
+                                    .await;
+                            }
+                        }
+                        Ok(ClientMessage::Ping) => {
+                            let _ = reply_tx.send(format.encode(Payload::Pong)).await;
+                        }
+                        Ok(ClientMessage::Recheck) => recheck.notify_one(),
+                        Ok(ClientMessage::Chat { body }) => {
+                            // Sending only fails when there are no subscribers, which is fine to ignore
+                            let _ = chat_tx.send(ChatMessage { client_id, body });
+                        }
+                        Ok(ClientMessage::Unknown) => debug!(text, "ignoring an unknown action"),
+                        Err(err) => debug!(%err, text, "ignoring an invalid message"),
+                    },
+                    // The client started the close handshake, so tear down the connection now
+                    Message::Close(_) => break,
+                    Message::Pong(_) => {
+                        pong_tx.send_replace(());
+                    }
+                    // axum answers pings for us, and binary messages have no meaning here
+                    Message::Ping(_) | Message::Binary(_) => {}
+                }
+            }
+        }
+        .instrument(info_span!("recv_task", client_id)),
+    );
+
+    // If the send task exits, abort the receive task. If the receive task exits, the send task
+    // flushes its last replies and then stops on its own.
+    tokio::select! {
+        _ = (&mut send_task) => recv_task.abort(),
+        _ = (&mut recv_task) => {
+            let _ = (&mut send_task).await;
+        }
+        // The send task sends a close frame on shutdown, so give it a moment to do so
+        _ = shutdown.changed() => {
+            let grace = Duration::from_secs(SHUTDOWN_GRACE_SECS);
+            let _ = timeout(grace, &mut send_task).await;
+            send_task.abort();
+            recv_task.abort();
+        }
+    };
+
+    // This client disconnected
+    let clients_count = slot.0.clone();
+    drop(slot);
+    metrics.clients_connected.dec();
+    let clients_count = clients_count.load(Ordering::Relaxed);
+    info!(client_id, clients_count, "client disconnected");
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[test]
+    fn payloads_are_versioned() {
+        let payload = Payload::Chat { body: "hi" };
+
+        let json = serde_json::to_value(payload.versioned()).unwrap();
+
+        assert_eq!(json, json!({ "v": 1, "type": "chat", "body": "hi" }));
+    }
+
+    #[test]
+    fn unknown_client_messages_are_tolerated() {
+        let msg = serde_json::from_str(r#"{"action":"dance"}"#).unwrap();
+
+        assert!(matches!(msg, ClientMessage::Unknown));
+    }
+
+    #[tokio::test]
+    async fn static_files_are_compressed() {
+        let static_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("static");
+        let request = Request::get("/index.html")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = static_files(static_folder).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    }
+
+    #[tokio::test]
+    async fn allowed_origins_get_cors_headers() {
+        let cors = parse_cors(Some("https://a.example, https://b.example".into())).unwrap();
+        let router = Router::new().route("/status", get(|| async {})).layer(cors);
+        let request = Request::get("/status")
+            .header(header::ORIGIN, "https://b.example")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = router.oneshot(request).await.unwrap();
+
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://b.example"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_static_folder_gets_a_placeholder() {
+        let static_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("missing");
+        let request = Request::get("/").body(Body::empty()).unwrap();
+
+        let response = static_files(static_folder).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, MISSING_STATIC_PAGE);
+    }
+
+    #[tokio::test]
+    async fn unknown_paths_get_the_index() {
+        let static_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("static");
+        let request = Request::get("/dashboard/foo").body(Body::empty()).unwrap();
+
+        let response = static_files(static_folder).oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+    }
+}
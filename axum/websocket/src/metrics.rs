@@ -0,0 +1,76 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+/// The Prometheus metrics exported on `/metrics`.
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub(crate) clients_connected: IntGauge,
+    target_up: IntGaugeVec,
+    check_latency: HistogramVec,
+    checks_total: IntCounterVec,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let clients_connected = IntGauge::new(
+            "ws_clients_connected",
+            "Number of clients connected to the WebSocket",
+        )?;
+        let target_up = IntGaugeVec::new(
+            Opts::new("target_up", "Whether the target was up at the last check"),
+            &["target"],
+        )?;
+        let check_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "health_check_latency_seconds",
+                "Time taken for a target to respond to a check",
+            ),
+            &["target"],
+        )?;
+        let checks_total = IntCounterVec::new(
+            Opts::new("health_checks_total", "Number of checks, by result"),
+            &["target", "result"],
+        )?;
+
+        registry.register(Box::new(clients_connected.clone()))?;
+        registry.register(Box::new(target_up.clone()))?;
+        registry.register(Box::new(check_latency.clone()))?;
+        registry.register(Box::new(checks_total.clone()))?;
+
+        Ok(Self {
+            registry,
+            clients_connected,
+            target_up,
+            check_latency,
+            checks_total,
+        })
+    }
+
+    /// Records the outcome of a single check of a target.
+    pub(crate) fn record_check(&self, target: &str, is_up: bool, latency_ms: Option<u64>) {
+        self.target_up
+            .with_label_values(&[target])
+            .set(is_up as i64);
+
+        let result = if is_up { "ok" } else { "fail" };
+        self.checks_total.with_label_values(&[target, result]).inc();
+
+        if let Some(latency_ms) = latency_ms {
+            self.check_latency
+                .with_label_values(&[target])
+                .observe(latency_ms as f64 / 1000.0);
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub(crate) fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        Ok(String::from_utf8(buffer).expect("the text format to be valid UTF-8"))
+    }
+}
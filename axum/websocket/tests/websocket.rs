@@ -0,0 +1,273 @@
+use std::{
+    collections::BTreeMap,
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    time::Duration,
+};
+
+use axum::{routing::get, Router, Server};
+use chrono::DateTime;
+use futures::StreamExt;
+use hyper::{Body, Client, Request, StatusCode};
+use serde::Deserialize;
+use shuttle_secrets::SecretStore;
+use sqlx::postgres::PgPoolOptions;
+use tokio::{net::TcpStream, time::timeout};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// The parts of the frames sent to clients that the tests look at.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Frame {
+    Welcome {
+        client_id: usize,
+    },
+    Status(Response),
+    Range {
+        items: Vec<Response>,
+        truncated: bool,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    clients_count: usize,
+    #[serde(rename = "dateTime")]
+    date_time: String,
+    targets: Vec<TargetStatus>,
+    paused: bool,
+    reason: String,
+}
+
+#[derive(Deserialize)]
+struct TargetStatus {
+    is_up: bool,
+    status_code: Option<u16>,
+}
+
+fn serve(router: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::from_tcp(listener)
+        .unwrap()
+        .serve(router.into_make_service());
+    tokio::spawn(server);
+
+    addr
+}
+
+/// Serves the app on an ephemeral port, monitoring a local target so the tests stay hermetic.
+async fn spawn_app() -> SocketAddr {
+    let target = serve(Router::new().route("/", get(|| async { "ok" })));
+    let secrets = SecretStore::new(BTreeMap::from([(
+        "STATUS_URI".to_string(),
+        format!("http://{target}/"),
+    )]));
+    // Nothing listens here, so storing the checks fails fast instead of needing a database
+    let pool = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_millis(100))
+        .connect_lazy("postgres://localhost:1/unused")
+        .unwrap();
+    let static_folder = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("static");
+
+    let app = websocket::app(static_folder, secrets, pool).await.unwrap();
+    serve(app)
+}
+
+async fn connect(addr: SocketAddr) -> WebSocket {
+    let (websocket, _) = connect_async(format!("ws://{addr}/websocket"))
+        .await
+        .unwrap();
+
+    websocket
+}
+
+async fn next_frame(websocket: &mut WebSocket) -> Frame {
+    let msg = timeout(Duration::from_secs(20), websocket.next())
+        .await
+        .expect("a frame before the timeout")
+        .expect("the connection to stay open")
+        .unwrap();
+
+    match msg {
+        Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        msg => panic!("expected a text frame, got {msg:?}"),
+    }
+}
+
+/// Asks for a recheck, which answers with the status pushed for it.
+async fn recheck(addr: SocketAddr) {
+    let request = Request::post(format!("http://{addr}/recheck"))
+        .body(Body::empty())
+        .unwrap();
+    let response = Client::new().request(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response).await.unwrap();
+    let response: Response = serde_json::from_slice(&body).unwrap();
+    assert_eq!(response.reason, "recheck");
+}
+
+async fn post(addr: SocketAddr, path: &str) -> StatusCode {
+    let request = Request::post(format!("http://{addr}{path}"))
+        .body(Body::empty())
+        .unwrap();
+
+    Client::new().request(request).await.unwrap().status()
+}
+
+/// Skips frames until the status pushed by a recheck.
+async fn next_recheck(websocket: &mut WebSocket) -> Response {
+    loop {
+        if let Frame::Status(response) = next_frame(websocket).await {
+            if response.reason == "recheck" {
+                return response;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn streams_the_status() {
+    let addr = spawn_app().await;
+    let mut websocket = connect(addr).await;
+
+    let Frame::Welcome { client_id } = next_frame(&mut websocket).await else {
+        panic!("expected the welcome frame first");
+    };
+    assert_eq!(client_id, 1);
+
+    recheck(addr).await;
+    let response = next_recheck(&mut websocket).await;
+
+    assert_eq!(response.clients_count, 1);
+    assert!(DateTime::parse_from_rfc3339(&response.date_time).is_ok());
+    assert_eq!(response.targets.len(), 1);
+    assert!(response.targets[0].is_up);
+    assert_eq!(response.targets[0].status_code, Some(200));
+}
+
+#[tokio::test]
+async fn counts_connected_clients() {
+    let addr = spawn_app().await;
+    let mut first = connect(addr).await;
+    let mut second = connect(addr).await;
+
+    recheck(addr).await;
+    assert_eq!(next_recheck(&mut first).await.clients_count, 2);
+    assert_eq!(next_recheck(&mut second).await.clients_count, 2);
+
+    second.close(None).await.unwrap();
+    while second.next().await.is_some() {}
+
+    // The server frees the client's slot just after closing the connection, so give it a moment
+    let mut clients_count = 0;
+    for _ in 0..10 {
+        recheck(addr).await;
+        clients_count = next_recheck(&mut first).await.clients_count;
+        if clients_count == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(clients_count, 1);
+}
+
+#[tokio::test]
+async fn pauses_and_resumes_the_checks() {
+    let addr = spawn_app().await;
+    let mut websocket = connect(addr).await;
+    recheck(addr).await;
+    next_recheck(&mut websocket).await;
+
+    assert_eq!(post(addr, "/admin/pause").await, StatusCode::NO_CONTENT);
+    let response = next_recheck(&mut websocket).await;
+    assert!(response.paused);
+    assert!(response.targets.is_empty());
+
+    assert_eq!(post(addr, "/admin/resume").await, StatusCode::NO_CONTENT);
+    let response = next_recheck(&mut websocket).await;
+    assert!(!response.paused);
+    assert_eq!(response.targets.len(), 1);
+    assert!(response.targets[0].is_up);
+}
+
+#[tokio::test]
+async fn queries_the_recent_history() {
+    let addr = spawn_app().await;
+    let mut websocket = connect(addr).await;
+    recheck(addr).await;
+    let pushed = next_recheck(&mut websocket).await;
+
+    let uri =
+        format!("http://{addr}/history/range?from=2000-01-01T00:00:00Z&to=2100-01-01T00:00:00Z");
+    let response = Client::new().get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response).await.unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(json["v"], 1);
+    let Frame::Range {
+        items: statuses,
+        truncated,
+    } = serde_json::from_value(json).unwrap()
+    else {
+        panic!("expected a range payload");
+    };
+    assert!(!truncated);
+    assert!(statuses
+        .iter()
+        .any(|status| status.date_time == pushed.date_time));
+
+    let uri =
+        format!("http://{addr}/history/range?from=2100-01-01T00:00:00Z&to=2000-01-01T00:00:00Z");
+    let response = Client::new().get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn rejects_malformed_queries_with_json() {
+    let addr = spawn_app().await;
+
+    for path in [
+        "/poll?since=bad",
+        "/history?hours=x",
+        "/history/range?from=bad&to=bad",
+    ] {
+        let uri = format!("http://{addr}{path}");
+        let response = Client::new().get(uri.parse().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{path}");
+
+        let body = hyper::body::to_bytes(response).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "bad_request", "{path}");
+    }
+}
+
+/// tungstenite can't compress frames yet, so the offer has to be declined rather than accepted.
+#[tokio::test]
+async fn ignores_compression_offers() {
+    let addr = spawn_app().await;
+    let mut request = format!("ws://{addr}/websocket")
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "sec-websocket-extensions",
+        "permessage-deflate".parse().unwrap(),
+    );
+
+    let (mut websocket, response) = connect_async(request).await.unwrap();
+
+    assert!(response.headers().get("sec-websocket-extensions").is_none());
+    assert!(matches!(
+        next_frame(&mut websocket).await,
+        Frame::Welcome { .. }
+    ));
+}